@@ -0,0 +1,96 @@
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use crate::QueryParams;
+
+/// A content key derived from the upstream path and the normalized transform
+/// parameters that produced a given output.
+pub type CacheKey = [u8; 32];
+
+/// An already-encoded transform result, ready to be streamed back to the
+/// client without touching the upstream or the resize pipeline again.
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Storage for transform results, keyed by [`CacheKey`]. Kept behind a trait
+/// so the in-memory implementation can later be swapped for a disk or
+/// object-storage backed one without touching the handler.
+pub trait ResultCache: Send + Sync {
+    fn get(&self, key: &CacheKey) -> Option<CachedResponse>;
+    fn put(&self, key: CacheKey, value: CachedResponse);
+}
+
+/// Hashes the upstream `path` together with the normalized query params and
+/// the format actually resolved for the response (which may come from
+/// `Accept`-header negotiation rather than `params` itself) so that two
+/// requests which negotiate to different output formats never collide on
+/// the same entry.
+pub fn cache_key(path: &str, params: &QueryParams, resolved_format: Option<image::ImageFormat>) -> CacheKey {
+    let canonical = format!(
+        "{}\nresolved_format={resolved_format:?}",
+        crate::canonical_params(path, params)
+    );
+
+    *blake3::hash(canonical.as_bytes()).as_bytes()
+}
+
+struct LruState {
+    entries: LruCache<CacheKey, CachedResponse>,
+    total_bytes: u64,
+}
+
+/// An in-memory [`ResultCache`] bounded by total byte size rather than entry
+/// count, evicting the least-recently-used entries once `max_bytes` is
+/// exceeded.
+pub struct LruResultCache {
+    state: Mutex<LruState>,
+    max_bytes: u64,
+}
+
+impl LruResultCache {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            state: Mutex::new(LruState {
+                entries: LruCache::unbounded(),
+                total_bytes: 0,
+            }),
+            max_bytes,
+        }
+    }
+}
+
+impl ResultCache for LruResultCache {
+    fn get(&self, key: &CacheKey) -> Option<CachedResponse> {
+        let mut state = self.state.lock().unwrap();
+
+        state.entries.get(key).cloned()
+    }
+
+    fn put(&self, key: CacheKey, value: CachedResponse) {
+        let size = value.bytes.len() as u64;
+
+        if size > self.max_bytes {
+            tracing::debug!("Skipping cache entry larger than QUICKLY_CACHE_SIZE ({size} bytes)");
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(old) = state.entries.put(key, value) {
+            state.total_bytes -= old.bytes.len() as u64;
+        }
+        state.total_bytes += size;
+
+        while state.total_bytes > self.max_bytes {
+            let Some((_, evicted)) = state.entries.pop_lru() else {
+                break;
+            };
+
+            state.total_bytes -= evicted.bytes.len() as u64;
+        }
+    }
+}
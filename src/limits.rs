@@ -0,0 +1,58 @@
+/// Resource limits guarding `resize_image` and the upstream fetch against
+/// requests that would otherwise allocate or decode an unbounded amount of
+/// memory, configured via `QUICKLY_MAX_*` environment variables.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_area: u64,
+    pub max_file_size: u64,
+}
+
+const DEFAULT_MAX_WIDTH: u32 = 4096;
+const DEFAULT_MAX_HEIGHT: u32 = 4096;
+const DEFAULT_MAX_AREA: u64 = 4096 * 4096;
+const DEFAULT_MAX_FILE_SIZE: u64 = 32 * 1024 * 1024;
+
+impl Limits {
+    pub fn from_env() -> Self {
+        Self {
+            max_width: env_var("QUICKLY_MAX_WIDTH", DEFAULT_MAX_WIDTH),
+            max_height: env_var("QUICKLY_MAX_HEIGHT", DEFAULT_MAX_HEIGHT),
+            max_area: env_var("QUICKLY_MAX_AREA", DEFAULT_MAX_AREA),
+            max_file_size: env_var("QUICKLY_MAX_FILE_SIZE", DEFAULT_MAX_FILE_SIZE),
+        }
+    }
+
+    /// Rejects a requested output size that exceeds the configured
+    /// dimension or area caps.
+    pub fn check_output_dimensions(&self, width: Option<u32>, height: Option<u32>) -> bool {
+        if width.is_some_and(|w| w > self.max_width) {
+            return false;
+        }
+
+        if height.is_some_and(|h| h > self.max_height) {
+            return false;
+        }
+
+        if let (Some(w), Some(h)) = (width, height) {
+            if (w as u64) * (h as u64) > self.max_area {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Rejects an upstream body that is too large to safely decode.
+    pub fn check_file_size(&self, len: usize) -> bool {
+        (len as u64) <= self.max_file_size
+    }
+}
+
+fn env_var<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::{FilterType, FitType, QueryParams};
+
+/// A named, fully-specified transform so callers can request
+/// `?preset=thumbnail` instead of spelling out raw dimensions, and operators
+/// can bound which transforms are permitted.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct Preset {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Option<FitType>,
+    pub format: Option<String>,
+    pub filter: Option<FilterType>,
+    pub quality: Option<u8>,
+}
+
+impl Preset {
+    /// Overlays this preset's params onto an incoming request, replacing any
+    /// raw width/height/fit/format/filter/quality the caller supplied
+    /// alongside `preset`.
+    pub fn apply(&self, mut params: QueryParams) -> QueryParams {
+        params.width = self.width;
+        params.height = self.height;
+        params.fit = self.fit.clone();
+        params.format = self.format.clone();
+        params.filter = self.filter.clone();
+        params.quality = self.quality;
+
+        params
+    }
+}
+
+/// The set of presets loaded from `QUICKLY_PRESETS`, plus whether ad-hoc
+/// (non-preset) transforms are permitted at all.
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct PresetConfig {
+    pub presets: HashMap<String, Preset>,
+    pub strict: bool,
+}
+
+/// Loads presets from a JSON or TOML file, dispatching on the file
+/// extension.
+pub fn load(path: &str) -> anyhow::Result<PresetConfig> {
+    let contents = std::fs::read_to_string(path)?;
+
+    if path.ends_with(".toml") {
+        Ok(toml::from_str(&contents)?)
+    } else {
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
@@ -0,0 +1,45 @@
+use crate::QueryParams;
+
+/// Verifies a request's `sig` query parameter against the configured signing
+/// key. Requests are treated as unauthenticated (always valid) when no key
+/// is configured, matching the "leave requests unauthenticated when no key
+/// is set" behavior the origin relies on.
+pub fn verify(signing_key: Option<&str>, path: &str, params: &QueryParams, sig: Option<&str>) -> bool {
+    let Some(key) = signing_key else {
+        return true;
+    };
+
+    let Some(sig) = sig else {
+        return false;
+    };
+
+    let expected = sign(key, path, params);
+
+    constant_time_eq(expected.as_bytes(), sig.as_bytes())
+}
+
+/// Computes the hex-encoded blake3 keyed hash over the canonical
+/// `path\nwidth=..\nheight=..\nfit=..\nformat=..\ncrop=..\nrotate=..\nflip=..\nblur=..\nfilter=..\nquality=..`
+/// representation of a transform request (see `canonical_params`), for an
+/// origin app to pre-sign the variants it intends to expose.
+pub fn sign(key: &str, path: &str, params: &QueryParams) -> String {
+    let mac = blake3::keyed_hash(derive_key(key).as_bytes(), canonical(path, params).as_bytes());
+
+    hex::encode(mac.as_bytes())
+}
+
+fn derive_key(key: &str) -> blake3::Hash {
+    blake3::hash(key.as_bytes())
+}
+
+fn canonical(path: &str, params: &QueryParams) -> String {
+    crate::canonical_params(path, params)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
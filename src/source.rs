@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+
+/// Where `quickly` fetches the original bytes for a `path` from, before any
+/// transform is applied. Lets the proxy sit in front of a plain HTTP origin,
+/// a local filesystem tree, or an S3-compatible bucket.
+#[async_trait]
+pub trait Source: Send + Sync {
+    async fn fetch(&self, path: &str) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Fetches from a single HTTP origin, joining `path` onto `upstream_uri`.
+/// This is the original behavior and remains the default.
+pub struct HttpSource {
+    pub upstream_uri: String,
+}
+
+#[async_trait]
+impl Source for HttpSource {
+    async fn fetch(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let bytes = surf::get(format!("{}/{}", self.upstream_uri, path))
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?
+            .body_bytes()
+            .await
+            .map_err(|err| anyhow::anyhow!(err))?;
+
+        Ok(bytes)
+    }
+}
+
+/// Fetches from a local directory, rejecting paths that would escape `root`.
+pub struct FsSource {
+    root: std::path::PathBuf,
+}
+
+impl FsSource {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl Source for FsSource {
+    async fn fetch(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let relative = std::path::Path::new(path.trim_start_matches('/'));
+
+        // `starts_with` on a lexically-joined path would accept `..`
+        // components that walk back out of `root` before the comparison
+        // ever runs, so reject anything but plain path segments up front.
+        let only_normal_components = relative
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)));
+
+        if !only_normal_components {
+            anyhow::bail!("path {path} escapes QUICKLY_SOURCE_ROOT");
+        }
+
+        Ok(async_std::fs::read(self.root.join(relative)).await?)
+    }
+}
+
+/// Fetches objects from an S3-compatible bucket, the common deployment for a
+/// user-uploaded-media proxy.
+pub struct S3Source {
+    bucket: s3::Bucket,
+}
+
+impl S3Source {
+    pub fn new(
+        bucket: String,
+        region: String,
+        access_key: String,
+        secret_key: String,
+    ) -> anyhow::Result<Self> {
+        let region: s3::Region = region.parse()?;
+        let credentials =
+            s3::creds::Credentials::new(Some(&access_key), Some(&secret_key), None, None, None)?;
+        let bucket = s3::Bucket::new(&bucket, region, credentials)?;
+
+        Ok(Self { bucket })
+    }
+}
+
+#[async_trait]
+impl Source for S3Source {
+    async fn fetch(&self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let response = self.bucket.get_object(path).await?;
+
+        Ok(response.bytes().to_vec())
+    }
+}